@@ -12,13 +12,17 @@ use cyclonedx_bom::prelude::*;
 use uuid::Uuid;
 
 pub fn create_bom(tool_version: String) -> CdxBom {
-    let mut bom = CdxBom::default();
-    bom.spec_version = SpecVersion::V1_5;
-    bom.serial_number = Some(UrnUuid::from(Uuid::new_v4()));
-    bom.version = 1;
+    let mut bom = CdxBom {
+        spec_version: SpecVersion::V1_5,
+        serial_number: Some(UrnUuid::from(Uuid::new_v4())),
+        version: 1,
+        ..Default::default()
+    };
 
-    let mut metadata = CdxMetadata::default();
-    metadata.timestamp = Some(DateTime::now().unwrap());
+    let mut metadata = CdxMetadata {
+        timestamp: Some(DateTime::now().unwrap()),
+        ..Default::default()
+    };
     
     let tool = CdxTool {
         vendor: Some(NormalizedString::new("cyclonedx-gentoo")),
@@ -32,6 +36,7 @@ pub fn create_bom(tool_version: String) -> CdxBom {
     bom
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_component(
     component_type: &str,
     group: &str,
@@ -63,12 +68,11 @@ pub fn create_component(
 
     if !purl_str.is_empty() {
         // pkg:gentoo/dev-libs/openssl@3.0.12
-        if let Some(rest) = purl_str.strip_prefix("pkg:") {
-            if let Some((p_type, rest)) = rest.split_once('/') {
-                if let Some((p_name, p_version)) = rest.split_once('@') {
-                     component.purl = Some(Purl::new(p_type, p_name, p_version).unwrap());
-                }
-            }
+        if let Some(rest) = purl_str.strip_prefix("pkg:")
+            && let Some((p_type, rest)) = rest.split_once('/')
+            && let Some((p_name, p_version)) = rest.split_once('@')
+        {
+            component.purl = Some(Purl::new(p_type, p_name, p_version).unwrap());
         }
     }
 
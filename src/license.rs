@@ -0,0 +1,80 @@
+//! Classification of the tokens of a Gentoo LICENSE value.
+
+/// Returns true if the token is a license name or an element of the LICENSE syntax
+/// (`||`, parentheses or a `flag?` / `!flag?` use conditional).
+pub fn is_known_token(token: &str) -> bool {
+    if token == "||" || token == "(" || token == ")" {
+        return true;
+    }
+    if let Some(flag) = token.strip_suffix('?') {
+        return is_use_flag(flag.strip_prefix('!').unwrap_or(flag));
+    }
+    is_license_name(token)
+}
+
+/// Splits a LICENSE value into its license names and the tokens that could not be classified.
+/// Syntax elements are known but dropped, as they are not licenses on their own.
+pub fn split_license(value: &str) -> (Vec<String>, Vec<String>) {
+    let mut names = Vec::new();
+    let mut unknown = Vec::new();
+    for token in value.split_whitespace() {
+        if is_license_name(token) {
+            names.push(token.to_string());
+        } else if !is_known_token(token) {
+            unknown.push(token.to_string());
+        }
+    }
+    (names, unknown)
+}
+
+/// License names may contain `[A-Za-z0-9+_.-]` and must not begin with `+`, `.` or `-`.
+fn is_license_name(token: &str) -> bool {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '_' | '.' | '-'))
+}
+
+/// USE flag names may contain `[A-Za-z0-9+_@-]` and must begin with a letter or digit.
+fn is_use_flag(flag: &str) -> bool {
+    let mut chars = flag.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphanumeric() => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '_' | '@' | '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_known_token() {
+        assert!(is_known_token("GPL-2+"));
+        assert!(is_known_token("_custom.license"));
+        assert!(is_known_token("||"));
+        assert!(is_known_token("("));
+        assert!(is_known_token(")"));
+        assert!(is_known_token("ssl?"));
+        assert!(is_known_token("!ssl?"));
+        assert!(is_known_token("python_targets_python3_12?"));
+
+        assert!(!is_known_token("-x?"));
+        assert!(!is_known_token("@x?"));
+        assert!(!is_known_token("!?"));
+        assert!(!is_known_token("+GPL"));
+        assert!(!is_known_token(".MIT"));
+        assert!(!is_known_token("-BSD"));
+        assert!(!is_known_token("GPL-2,"));
+    }
+
+    #[test]
+    fn test_split_license() {
+        let (names, unknown) = split_license("|| ( MIT !ssl? ( GPL-2+ ) ) .BSD @x?");
+        assert_eq!(names, vec!["MIT", "GPL-2+"]);
+        assert_eq!(unknown, vec![".BSD", "@x?"]);
+    }
+}
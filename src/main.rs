@@ -1,9 +1,13 @@
 mod cyclonedx;
+mod license;
+mod watch;
 
 use crate::cyclonedx::{create_bom, create_component};
+use crate::license::split_license;
 use clap::{Arg, Command};
+use std::io::Write;
 use std::path::Path;
-use vardbpkg::parse_vardb;
+use vardbpkg::{parse_vardb, VarDbPkg};
 use cyclonedx_bom::models::bom::Bom;
 use cyclonedx_bom::models::component::Components;
 
@@ -23,6 +27,10 @@ struct Args {
     only_master: bool,
     /// Optional version for the top-level component.
     version: Option<String>,
+    /// If true, LICENSE tokens that cannot be classified abort the run instead of being dropped.
+    fail_on_unknown_license_tokens: bool,
+    /// Optional path to the state file used to report changes since the last scan.
+    watch_state: Option<String>,
 }
 
 fn main() -> std::io::Result<()> {
@@ -34,13 +42,53 @@ fn main() -> std::io::Result<()> {
         name: matches.get_one::<String>("name").cloned(),
         only_master: matches.get_flag("only-master"),
         version: matches.get_one::<String>("version").cloned(),
+        fail_on_unknown_license_tokens: matches.get_flag("fail-on-unknown-license-tokens"),
+        watch_state: matches.get_one::<String>("watch-state").cloned(),
     };
 
-    let bom = generate_bom(&args, None)?;
+    let bom = match generate_bom(&args, None) {
+        Ok(bom) => bom,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    let mut stderr = std::io::stderr();
+    if let Err(e) = write_output(&args, bom, &mut stdout, &mut stderr) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Writes the BOM to `out` and, with a watch state, the changes since the last scan to `changes_out`.
+/// The state is only stored once the BOM was written, otherwise the changes would be lost.
+fn write_output<O: Write, C: Write>(
+    args: &Args,
+    bom: Bom,
+    out: &mut O,
+    changes_out: &mut C,
+) -> std::io::Result<()> {
+    let fingerprint = args.watch_state.as_ref().map(|_| watch::fingerprint(&bom));
 
     let mut output = Vec::new();
-    bom.output_as_json_v1_5(&mut output).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    println!("{}", String::from_utf8_lossy(&output));
+    bom.output_as_json_v1_5(&mut output).map_err(std::io::Error::other)?;
+    writeln!(out, "{}", String::from_utf8_lossy(&output))?;
+    out.flush()?;
+
+    if let (Some(state_path), Some(fingerprint)) = (&args.watch_state, &fingerprint) {
+        let changes = watch::update_state(Path::new(state_path), fingerprint).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("cannot update watch state {}: {}", state_path, e),
+            )
+        })?;
+        for change in changes {
+            writeln!(changes_out, "{}", change)?;
+        }
+    }
 
     Ok(())
 }
@@ -49,19 +97,59 @@ fn generate_bom(args: &Args, tool_version: Option<String>) -> std::io::Result<Bo
     let db_path = args.dir.as_deref().unwrap_or(DEFAULT_VAR_DB_PKG_PATH);
     let packages = parse_vardb(Path::new(db_path));
 
+    let mut unknown_license_tokens = Vec::new();
     let mut bom = create_bom(tool_version.unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string()));
 
     if args.group.is_some() || args.name.is_some() || args.version.is_some() {
-        let metadata_component = create_component(
-            "application",
-            args.group.as_deref().unwrap_or_default(),
-            args.name.as_deref().unwrap_or_default(),
-            args.version.as_deref().unwrap_or_default(),
-            "",
-            Vec::new(),
-            "",
-            Vec::new(),
-        );
+        let installed = args.name.as_deref().and_then(|name| {
+            find_installed(&packages, name, args.group.as_deref(), args.version.as_deref())
+        });
+
+        let metadata_component = match installed {
+            Some(pkg) => {
+                if let Some(group) = args.group.as_deref().filter(|group| *group != pkg.category) {
+                    eprintln!(
+                        "Warning: group {} does not match the category of {}/{}, using {}.",
+                        group, pkg.category, pkg.package, pkg.category
+                    );
+                }
+                create_component(
+                    "application",
+                    &pkg.category,
+                    &pkg.package,
+                    args.version.as_deref().unwrap_or(&pkg.version),
+                    &pkg.description,
+                    package_licenses(pkg, &mut unknown_license_tokens),
+                    "",
+                    split_list(&pkg.homepage),
+                )
+            }
+            None => {
+                if let Some(name) = args.name.as_deref() {
+                    let atom = if name.contains('/') {
+                        Some(name.to_string())
+                    } else {
+                        args.group.as_deref().map(|group| format!("{}/{}", group, name))
+                    };
+                    if let Some(atom) = atom {
+                        eprintln!(
+                            "Warning: package {} is not installed in {}, top level component will only carry the given values.",
+                            atom, db_path
+                        );
+                    }
+                }
+                create_component(
+                    "application",
+                    args.group.as_deref().unwrap_or_default(),
+                    args.name.as_deref().unwrap_or_default(),
+                    args.version.as_deref().unwrap_or_default(),
+                    "",
+                    Vec::new(),
+                    "",
+                    Vec::new(),
+                )
+            }
+        };
         if let Some(ref mut metadata) = bom.metadata {
             metadata.component = Some(metadata_component);
         }
@@ -69,20 +157,9 @@ fn generate_bom(args: &Args, tool_version: Option<String>) -> std::io::Result<Bo
 
     if !args.only_master {
         let mut components = Vec::new();
-        for pkg in packages {
-            let mut licenses = Vec::new();
-            for lic in pkg.license.split(' ') {
-                if !lic.is_empty() {
-                    licenses.push(lic.to_string());
-                }
-            }
-
-            let mut homepages = Vec::new();
-            for hp in pkg.homepage.split(' ') {
-                if !hp.is_empty() {
-                    homepages.push(hp.to_string());
-                }
-            }
+        for pkg in &packages {
+            let licenses = package_licenses(pkg, &mut unknown_license_tokens);
+            let homepages = split_list(&pkg.homepage);
 
             let component = create_component(
                 "library",
@@ -102,9 +179,88 @@ fn generate_bom(args: &Args, tool_version: Option<String>) -> std::io::Result<Bo
         bom.components = Some(Components(components));
     }
 
+    // The metadata component may be one of the installed packages, so tokens can be seen twice
+    unknown_license_tokens.sort();
+    unknown_license_tokens.dedup();
+    if !unknown_license_tokens.is_empty() {
+        if args.fail_on_unknown_license_tokens {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown license tokens:\n{}", unknown_license_tokens.join("\n")),
+            ));
+        }
+        for token in &unknown_license_tokens {
+            eprintln!("Warning: dropped unknown license token {}", token);
+        }
+    }
+
     Ok(bom)
 }
 
+/// Returns the license names of a package and records the unknown ones
+/// as `category/package-version: token` in `unknown_tokens`.
+fn package_licenses(pkg: &VarDbPkg, unknown_tokens: &mut Vec<String>) -> Vec<String> {
+    let (licenses, unknown) = split_license(&pkg.license);
+    for token in unknown {
+        unknown_tokens.push(format!(
+            "{}/{}-{}: {}",
+            pkg.category, pkg.package, pkg.version, token
+        ));
+    }
+    licenses
+}
+
+/// Splits a space separated vardb value (e.g. HOMEPAGE) into its entries.
+fn split_list(value: &str) -> Vec<String> {
+    value.split_whitespace().map(str::to_string).collect()
+}
+
+/// Looks up the installed package for an atom given as `category/package`, `=category/package-version`
+/// or, together with a group, as bare `package`. Bare names without a group are not looked up.
+/// Without an exact version in the atom, the given version is preferred if several versions are installed.
+fn find_installed<'a>(
+    packages: &'a [VarDbPkg],
+    atom: &str,
+    group: Option<&str>,
+    version: Option<&str>,
+) -> Option<&'a VarDbPkg> {
+    let (exact, atom) = match atom.strip_prefix('=') {
+        Some(atom) => (true, atom),
+        None => (false, atom),
+    };
+    let (category, package) = match atom.split_once('/') {
+        Some((category, package)) => (category, package),
+        None => (group?, atom),
+    };
+    let (package, version) = if exact {
+        let (package, exact_version) = split_package_version(package)?;
+        (package, Some(exact_version))
+    } else {
+        (package, version)
+    };
+
+    let mut candidates = packages
+        .iter()
+        .filter(|pkg| pkg.package == package && pkg.category == category);
+    let first = candidates.next()?;
+    match version {
+        Some(version) if first.version != version => {
+            let found = candidates.find(|pkg| pkg.version == version);
+            if exact { found } else { found.or(Some(first)) }
+        }
+        _ => Some(first),
+    }
+}
+
+/// Splits `package-version` at the first dash that is followed by a digit.
+fn split_package_version(value: &str) -> Option<(&str, &str)> {
+    value
+        .match_indices('-')
+        .map(|(i, _)| i)
+        .find(|&i| i > 0 && value[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map(|i| (&value[..i], &value[i + 1..]))
+}
+
 fn cli() -> Command {
     Command::new("cyclonedx-gentoo")
         .about("Generates SBOM in CycloneDX format for Gentoo-Linux Portage Packet database")
@@ -118,6 +274,12 @@ fn cli() -> Command {
                 .help("(Optional) Group value to assign to top level component.")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("fail-on-unknown-license-tokens")
+                .long("fail-on-unknown-license-tokens")
+                .help("(Optional) Exit with an error listing every LICENSE token that cannot be classified, instead of dropping it with a warning.")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("help")
                 .short('h')
@@ -138,7 +300,7 @@ fn cli() -> Command {
                 .short('n')
                 .long("name")
                 .value_name("NAME")
-                .help("(Optional) Name value to assign to top level component.")
+                .help("(Optional) Name value to assign to top level component. If it names an installed package (app-misc/foo, =app-misc/foo-1.0, or foo with --group), its description, licenses and homepage are used as well.")
                 .num_args(1),
         )
         .arg(
@@ -156,6 +318,15 @@ fn cli() -> Command {
                 .help("(Optional) Version value to assign to top level component.")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("watch-state")
+                .short('w')
+                .long("watch-state")
+                .value_name("FILE")
+                .help("(Optional) Keep a fingerprint of the last scan in this file and print the packages changed since then to stderr.")
+                .num_args(1)
+                .conflicts_with("only-master"),
+        )
 }
 
 #[cfg(test)]
@@ -196,6 +367,8 @@ mod tests {
             name: None,
             only_master: false,
             version: None,
+            fail_on_unknown_license_tokens: false,
+            watch_state: None,
         };
 
         let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
@@ -224,4 +397,267 @@ mod tests {
         assert_eq!(external_references.0[0].url.to_string(), "https://example.com/mock-pkg");
     }
 
+    #[test]
+    fn test_watch_state_reports_changes_since_last_scan() {
+        use std::fs;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("pkg");
+        let state_path = temp_dir.path().join("state");
+
+        let write_pkg = |category: &str, pf: &str| {
+            let pkg_dir = db_path.join(category).join(pf);
+            fs::create_dir_all(&pkg_dir).unwrap();
+            fs::write(pkg_dir.join("CATEGORY"), format!("{}\n", category)).unwrap();
+            fs::write(pkg_dir.join("PF"), format!("{}\n", pf)).unwrap();
+        };
+        write_pkg("app-misc", "mock-pkg-1.2.3");
+        write_pkg("dev-libs", "old-lib-0.9");
+
+        let args = Args {
+            group: None,
+            dir: Some(db_path.to_str().unwrap().to_string()),
+            name: None,
+            only_master: false,
+            version: None,
+            fail_on_unknown_license_tokens: false,
+            watch_state: Some(state_path.to_str().unwrap().to_string()),
+        };
+
+        // Runs the scan like main does and returns the lines reported as changed
+        let scan = |args: &Args| -> Vec<String> {
+            let bom = generate_bom(args, Some("0.1.0".to_string())).unwrap();
+            let mut out = Vec::new();
+            let mut changes_out = Vec::new();
+            write_output(args, bom, &mut out, &mut changes_out).unwrap();
+            assert!(String::from_utf8(out).unwrap().contains("\"bomFormat\""));
+            String::from_utf8(changes_out).unwrap().lines().map(str::to_string).collect()
+        };
+
+        // First run only records the baseline
+        assert!(scan(&args).is_empty());
+
+        // Upgrade one package, remove one and install a new one
+        fs::remove_dir_all(db_path.join("app-misc").join("mock-pkg-1.2.3")).unwrap();
+        fs::remove_dir_all(db_path.join("dev-libs")).unwrap();
+        write_pkg("app-misc", "mock-pkg-1.3.0");
+        write_pkg("sys-apps", "new-tool-2.0");
+
+        let lines = scan(&args);
+        assert_eq!(lines.len(), 3);
+        assert!(lines.contains(&"~ pkg:gentoo/app-misc%2Fmock-pkg@1.2.3 -> pkg:gentoo/app-misc%2Fmock-pkg@1.3.0".to_string()));
+        assert!(lines.contains(&"+ pkg:gentoo/sys-apps%2Fnew-tool@2.0".to_string()));
+        assert!(lines.contains(&"- pkg:gentoo/dev-libs%2Fold-lib@0.9".to_string()));
+
+        // Nothing changed since the previous run
+        assert!(scan(&args).is_empty());
+
+        // A second slot of an installed package is reported as its own purl
+        write_pkg("app-misc", "mock-pkg-2.0");
+
+        // If the BOM cannot be written, the state is kept and the change is reported next time
+        let state_before = fs::read_to_string(&state_path).unwrap();
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        assert!(write_output(&args, bom, &mut FailingWriter, &mut Vec::new()).is_err());
+        assert_eq!(fs::read_to_string(&state_path).unwrap(), state_before);
+
+        assert_eq!(scan(&args), vec!["+ pkg:gentoo/app-misc%2Fmock-pkg@2.0"]);
+
+        fs::remove_dir_all(db_path.join("app-misc").join("mock-pkg-2.0")).unwrap();
+        assert_eq!(scan(&args), vec!["- pkg:gentoo/app-misc%2Fmock-pkg@2.0"]);
+    }
+
+    #[test]
+    fn test_watch_state_error_names_state_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state_path = temp_dir.path().join("missing-dir").join("state");
+        let args = Args {
+            group: None,
+            dir: Some(temp_dir.path().to_str().unwrap().to_string()),
+            name: None,
+            only_master: false,
+            version: None,
+            fail_on_unknown_license_tokens: false,
+            watch_state: Some(state_path.to_str().unwrap().to_string()),
+        };
+
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        let err = write_output(&args, bom, &mut Vec::new(), &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "cannot update watch state {}: No such file or directory (os error 2)",
+                state_path.display()
+            )
+        );
+    }
+
+    /// Writer standing in for a closed stdout.
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_metadata_component_from_installed_atom() {
+        let db_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join("vardb");
+        let mut args = Args {
+            group: None,
+            dir: Some(db_path.to_str().unwrap().to_string()),
+            name: Some("app-misc/mock-app".to_string()),
+            only_master: true,
+            version: None,
+            fail_on_unknown_license_tokens: false,
+            watch_state: None,
+        };
+
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        let metadata = bom.metadata.as_ref().unwrap();
+        let component = metadata.component.as_ref().expect("Should have metadata component");
+        assert_eq!(component.name.to_string(), "mock-app");
+        assert_eq!(component.group.as_ref().unwrap().to_string(), "app-misc");
+        assert_eq!(component.version.as_ref().unwrap().to_string(), "2.0.1");
+        assert_eq!(component.description.as_ref().unwrap().to_string(), "A mock application for testing");
+
+        let licenses: Vec<String> = component
+            .licenses
+            .as_ref()
+            .expect("Should have licenses")
+            .0
+            .iter()
+            .map(|l| match l {
+                cyclonedx_bom::models::license::LicenseChoice::Expression(e) => e.to_string(),
+                _ => panic!("Licenses should be expressions"),
+            })
+            .collect();
+        assert_eq!(licenses, vec!["GPL-2", "BSD"]);
+
+        let external_references = component.external_references.as_ref().expect("Should have external references");
+        assert_eq!(external_references.0.len(), 2);
+        assert_eq!(external_references.0[0].url.to_string(), "https://example.com/mock-app");
+
+        // An explicit version wins over the installed one
+        args.version = Some("2024.01".to_string());
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        let component = bom.metadata.as_ref().unwrap().component.as_ref().unwrap();
+        assert_eq!(component.version.as_ref().unwrap().to_string(), "2024.01");
+        assert!(component.licenses.is_some());
+
+        // The category of the atom wins over a different group
+        args.group = Some("sys-libs".to_string());
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        let component = bom.metadata.as_ref().unwrap().component.as_ref().unwrap();
+        assert_eq!(component.group.as_ref().unwrap().to_string(), "app-misc");
+        args.group = None;
+
+        // A bare name without group is a plain product name, even if such a package is installed
+        args.name = Some("mock-app".to_string());
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        let component = bom.metadata.as_ref().unwrap().component.as_ref().unwrap();
+        assert_eq!(component.name.to_string(), "mock-app");
+        assert!(component.group.is_none());
+        assert!(component.licenses.is_none());
+
+        // Atoms that are not installed only carry the given values
+        args.name = Some("app-misc/not-installed".to_string());
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        let component = bom.metadata.as_ref().unwrap().component.as_ref().unwrap();
+        assert_eq!(component.name.to_string(), "app-misc/not-installed");
+        assert!(component.description.is_none());
+        assert!(component.licenses.is_none());
+    }
+
+    #[test]
+    fn test_fail_on_unknown_license_tokens() {
+        use std::fs;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path();
+
+        let pkg_dir = db_path.join("app-misc").join("bad-license-1.0");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(pkg_dir.join("CATEGORY"), "app-misc\n").unwrap();
+        fs::write(pkg_dir.join("PF"), "bad-license-1.0\n").unwrap();
+        fs::write(pkg_dir.join("LICENSE"), "MIT GPL-2, ssl? ( OpenSSL )\n").unwrap();
+
+        let mut args = Args {
+            group: None,
+            dir: Some(db_path.to_str().unwrap().to_string()),
+            name: None,
+            only_master: false,
+            version: None,
+            fail_on_unknown_license_tokens: true,
+            watch_state: None,
+        };
+
+        let err = generate_bom(&args, Some("0.1.0".to_string())).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let message = err.to_string();
+        assert!(message.contains("app-misc/bad-license-1.0: GPL-2,"));
+        assert!(!message.contains("MIT"));
+        assert!(!message.contains("OpenSSL"));
+
+        // Without the gate the unknown token is dropped
+        args.fail_on_unknown_license_tokens = false;
+        let bom = generate_bom(&args, Some("0.1.0".to_string())).unwrap();
+        let pkg = &bom.components.as_ref().unwrap().0[0];
+        let licenses: Vec<String> = pkg
+            .licenses
+            .as_ref()
+            .unwrap()
+            .0
+            .iter()
+            .map(|l| match l {
+                cyclonedx_bom::models::license::LicenseChoice::Expression(e) => e.to_string(),
+                _ => panic!("Licenses should be expressions"),
+            })
+            .collect();
+        assert_eq!(licenses, vec!["MIT", "OpenSSL"]);
+
+        // A package that is also the metadata component reports its tokens once
+        args.fail_on_unknown_license_tokens = true;
+        args.name = Some("app-misc/bad-license".to_string());
+        let message = generate_bom(&args, Some("0.1.0".to_string())).unwrap_err().to_string();
+        assert_eq!(message.matches("app-misc/bad-license-1.0: GPL-2,").count(), 1);
+    }
+
+    #[test]
+    fn test_find_installed() {
+        let pkg = |category: &str, package: &str, version: &str| VarDbPkg {
+            category: category.to_string(),
+            package: package.to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        };
+        let packages = vec![
+            pkg("app-misc", "tool", "1.0"),
+            pkg("dev-util", "tool", "2.0"),
+            pkg("app-misc", "my-app", "1.0"),
+            pkg("app-misc", "my-app", "1.1"),
+        ];
+
+        // Bare names are only looked up together with a group
+        assert!(find_installed(&packages, "tool", None, None).is_none());
+        let found = find_installed(&packages, "tool", Some("dev-util"), None).unwrap();
+        assert_eq!(found.version, "2.0");
+        assert!(find_installed(&packages, "missing", Some("app-misc"), None).is_none());
+
+        // The given version is preferred among several installed versions
+        let found = find_installed(&packages, "app-misc/my-app", None, Some("1.1")).unwrap();
+        assert_eq!(found.version, "1.1");
+        let found = find_installed(&packages, "app-misc/my-app", None, Some("9.9")).unwrap();
+        assert_eq!(found.version, "1.0");
+
+        // A versioned atom requires exactly that version
+        let found = find_installed(&packages, "=app-misc/my-app-1.1", None, None).unwrap();
+        assert_eq!(found.package, "my-app");
+        assert_eq!(found.version, "1.1");
+        assert!(find_installed(&packages, "=app-misc/my-app-2.0", None, None).is_none());
+        assert!(find_installed(&packages, "=app-misc/my-app", None, None).is_none());
+    }
 }
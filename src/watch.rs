@@ -0,0 +1,180 @@
+//! Compact fingerprint of the last scan, used to report drift between runs.
+
+use cyclonedx_bom::models::bom::Bom;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Maps a versionless purl (e.g. `pkg:gentoo/dev-libs%2Fopenssl`) to its installed version(s).
+/// Packages installed in several slots keep all their versions.
+pub type Fingerprint = BTreeMap<String, BTreeSet<String>>;
+
+/// A single difference between two fingerprints, always concerning one version of a package.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Added { purl: String, version: String },
+    Removed { purl: String, version: String },
+    Updated { purl: String, old: String, new: String },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { purl, version } => write!(f, "+ {}@{}", purl, version),
+            Change::Removed { purl, version } => write!(f, "- {}@{}", purl, version),
+            Change::Updated { purl, old, new } => write!(f, "~ {}@{} -> {}@{}", purl, old, purl, new),
+        }
+    }
+}
+
+/// Builds the fingerprint from the components of the given BOM.
+pub fn fingerprint(bom: &Bom) -> Fingerprint {
+    let mut state = Fingerprint::new();
+    if let Some(ref components) = bom.components {
+        for component in &components.0 {
+            let Some(ref purl) = component.purl else {
+                continue;
+            };
+            let purl = purl.to_string();
+            let (name, version) = purl.rsplit_once('@').unwrap_or((&purl, ""));
+            state
+                .entry(name.to_string())
+                .or_default()
+                .insert(version.to_string());
+        }
+    }
+    state
+}
+
+/// Reads a fingerprint previously written by [`save_state`].
+/// Returns `None` if the state file does not exist yet or is empty, and fails on malformed lines.
+pub fn load_state(path: &Path) -> std::io::Result<Option<Fingerprint>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if content.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let mut state = Fingerprint::new();
+    for (number, line) in content.lines().enumerate() {
+        let Some((purl, versions)) = line.split_once('\t') else {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("malformed line {}: {}", number + 1, line),
+            ));
+        };
+        state.insert(
+            purl.to_string(),
+            versions.split_whitespace().map(str::to_string).collect(),
+        );
+    }
+    Ok(Some(state))
+}
+
+/// Writes the fingerprint as one `purl<TAB>versions` line per package, versions separated by spaces.
+/// The file is written next to the state file first and then renamed, so it is never left partial.
+pub fn save_state(path: &Path, state: &Fingerprint) -> std::io::Result<()> {
+    let mut content = String::new();
+    for (purl, versions) in state {
+        let versions: Vec<&str> = versions.iter().map(String::as_str).collect();
+        content.push_str(&format!("{}\t{}\n", purl, versions.join(" ")));
+    }
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Lists the package versions that were added, removed or changed between `old` and `new`.
+/// A package with exactly one version replaced by another is reported as updated.
+pub fn diff(old: &Fingerprint, new: &Fingerprint) -> Vec<Change> {
+    let empty = BTreeSet::new();
+    let mut changes = Vec::new();
+    let purls: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for purl in purls {
+        let old_versions = old.get(purl).unwrap_or(&empty);
+        let new_versions = new.get(purl).unwrap_or(&empty);
+        let removed: Vec<&String> = old_versions.difference(new_versions).collect();
+        let added: Vec<&String> = new_versions.difference(old_versions).collect();
+
+        if let ([old_version], [new_version]) = (removed.as_slice(), added.as_slice()) {
+            changes.push(Change::Updated {
+                purl: purl.clone(),
+                old: (*old_version).clone(),
+                new: (*new_version).clone(),
+            });
+            continue;
+        }
+        for version in added {
+            changes.push(Change::Added {
+                purl: purl.clone(),
+                version: version.clone(),
+            });
+        }
+        for version in removed {
+            changes.push(Change::Removed {
+                purl: purl.clone(),
+                version: version.clone(),
+            });
+        }
+    }
+    changes
+}
+
+/// Compares the fingerprint against the stored state, stores the new fingerprint and returns the changes.
+/// The first run only records the baseline and reports no changes.
+pub fn update_state(path: &Path, current: &Fingerprint) -> std::io::Result<Vec<Change>> {
+    let changes = match load_state(path)? {
+        Some(previous) => diff(&previous, current),
+        None => Vec::new(),
+    };
+    save_state(path, current)?;
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_state_without_baseline() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state_path = temp_dir.path().join("state");
+        assert!(load_state(&state_path).unwrap().is_none());
+
+        fs::write(&state_path, "").unwrap();
+        assert!(load_state(&state_path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_state_rejects_malformed_lines() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state_path = temp_dir.path().join("state");
+        fs::write(&state_path, "pkg:gentoo/app-misc%2Ffoo\t1.0\npkg:gentoo/app-mi").unwrap();
+
+        let err = load_state(&state_path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state_path = temp_dir.path().join("state");
+        let mut state = Fingerprint::new();
+        state.insert(
+            "pkg:gentoo/app-misc%2Ffoo".to_string(),
+            ["1.0".to_string(), "2.0".to_string()].into(),
+        );
+
+        save_state(&state_path, &state).unwrap();
+        assert_eq!(load_state(&state_path).unwrap(), Some(state));
+        assert!(!temp_dir.path().join("state.tmp").exists());
+    }
+}